@@ -4,13 +4,22 @@
 
 //! Entrypoint code
 
-use core::arch::naked_asm;
+use core::arch::{global_asm, naked_asm};
+
+// Default no-op implementation of the `pre_init` hook, used when the application does not
+// register one with `pre_init!`. Must be weak, since only the application (if anyone) knows
+// whether it needs to run code before bss is zeroed and the stack is set up.
+global_asm!(".weak pre_init", "pre_init:", "ret");
 
 /// This is a generic entry point for an image. It carries out the operations required to prepare the
 /// loaded image to be run. Specifically, it zeroes the bss section using registers x25 and above,
 /// prepares the stack, enables floating point, and sets up the exception vector. It preserves x0-x3
 /// for the Rust entry point, as these may contain boot parameters.
 ///
+/// Immediately after the MMU is enabled, but before bss is zeroed or the stack is set up, it calls
+/// the `pre_init` hook registered with [`pre_init!`][crate::pre_init], or a no-op default if the
+/// application did not register one.
+///
 /// # Safety
 ///
 /// This function is marked unsafe because it should never be called by anyone. The linker is
@@ -25,6 +34,18 @@ unsafe extern "C" fn entry() -> ! {
         r"add \reg, \reg, :lo12:\sym",
         ".endm",
         "bl enable_mmu",
+        // Run the application's pre-init hook, if any. At this point only the MMU is set up: bss
+        // has not been zeroed and there is no stack, so the hook must not rely on either. The boot
+        // parameters in x0-x3 are kept in x19-x22, which the hook must preserve, across the call.
+        "mov x19, x0",
+        "mov x20, x1",
+        "mov x21, x2",
+        "mov x22, x3",
+        "bl pre_init",
+        "mov x0, x19",
+        "mov x1, x20",
+        "mov x2, x21",
+        "mov x3, x22",
         // Disable trapping floating point access in EL1.
         "mrs x30, cpacr_el1",
         "orr x30, x30, #(0x3 << 20)",
@@ -0,0 +1,346 @@
+// Copyright 2025 The aarch64-rt Authors.
+// This project is dual-licensed under Apache 2.0 and MIT terms.
+// See LICENSE-APACHE and LICENSE-MIT for details.
+
+//! Exception handling.
+//!
+//! Enabling the `exceptions` feature programs `vbar_el*` (via [`set_exception_vector`][super::set_exception_vector])
+//! to point at a vector table provided by this crate. Every one of the 16 vector table entries
+//! saves the interrupted context into an [`ExceptionFrame`] and dispatches to one of four named
+//! handlers, `SyncException`, `Irq`, `Fiq` or `SError`, passing it the frame together with the
+//! [`Syndrome`] decoded from `ESR_ELx`/`FAR_ELx`.
+//!
+//! Applications register a handler with the [`exception!`] macro; any vector that is not
+//! registered falls back to `DefaultHandler`, which panics with the decoded syndrome and a
+//! register dump unless the application overrides it the same way:
+//!
+//! ```rust
+//! use aarch64_rt::exception;
+//!
+//! exception!(SyncException, fn handle_sync(frame, syndrome) {
+//!     panic!("sync exception: {syndrome:?} at elr={:#x}", frame.elr);
+//! });
+//! ```
+
+use core::arch::{asm, global_asm};
+
+global_asm!(include_str!("exceptions.S"));
+
+/// The register state saved by the vector table when an exception is taken.
+///
+/// The assembly stub restores this frame and executes `eret` once the handler returns, so a
+/// handler may mutate `elr` (e.g. to step past a faulting instruction) to control where execution
+/// resumes.
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionFrame {
+    /// The general-purpose registers x0 to x29, in order.
+    pub x: [u64; 30],
+    /// The link register (x30) at the time the exception was taken.
+    pub lr: u64,
+    /// The exception link register (`ELR_ELx`), i.e. the address execution will resume at.
+    pub elr: u64,
+    /// The saved program status register (`SPSR_ELx`) at the time of the exception.
+    pub spsr: u64,
+    _reserved: u64,
+}
+
+/// The cause of an exception, decoded from `ESR_ELx` (and `FAR_ELx` for aborts).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syndrome {
+    /// A synchronous exception.
+    Synchronous(SyncReason),
+    /// A physical IRQ.
+    Irq,
+    /// A physical FIQ.
+    Fiq,
+    /// A physical SError (asynchronous abort).
+    SError {
+        /// The Instruction Specific Syndrome field of `ESR_ELx`.
+        iss: u32,
+    },
+}
+
+/// The reason for a synchronous exception, decoded from the Exception Class (`EC`) field of
+/// `ESR_ELx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncReason {
+    /// A data abort, with the faulting address read from `FAR_ELx`.
+    DataAbort {
+        /// The faulting virtual address.
+        far: u64,
+        /// The Instruction Specific Syndrome field of `ESR_ELx`.
+        iss: u32,
+    },
+    /// An instruction abort, with the faulting address read from `FAR_ELx`.
+    InstructionAbort {
+        /// The faulting virtual address.
+        far: u64,
+        /// The Instruction Specific Syndrome field of `ESR_ELx`.
+        iss: u32,
+    },
+    /// An `SVC` instruction exception, with the 16-bit immediate encoded in the instruction.
+    Svc(u16),
+    /// Any other exception class, identified by its raw `EC` value.
+    Other {
+        /// The raw Exception Class field of `ESR_ELx`.
+        ec: u8,
+        /// The Instruction Specific Syndrome field of `ESR_ELx`.
+        iss: u32,
+    },
+}
+
+impl Syndrome {
+    /// Decodes the syndrome for the given exception `kind`, as passed by the vector table
+    /// (0 = synchronous, 1 = IRQ, 2 = FIQ, 3 = SError).
+    fn decode(kind: u64) -> Self {
+        match kind {
+            0 => Syndrome::Synchronous(SyncReason::decode(read_esr_elx())),
+            1 => Syndrome::Irq,
+            2 => Syndrome::Fiq,
+            3 => Syndrome::SError {
+                iss: (read_esr_elx() & 0x1ff_ffff) as u32,
+            },
+            _ => unreachable!("the vector table only ever passes a kind in 0..=3"),
+        }
+    }
+}
+
+impl SyncReason {
+    fn decode(esr: u64) -> Self {
+        let ec = ((esr >> 26) & 0x3f) as u8;
+        let iss = (esr & 0x1ff_ffff) as u32;
+        match ec {
+            0x24 | 0x25 => SyncReason::DataAbort {
+                far: read_far_elx(),
+                iss,
+            },
+            0x20 | 0x21 => SyncReason::InstructionAbort {
+                far: read_far_elx(),
+                iss,
+            },
+            0x15 => SyncReason::Svc((iss & 0xffff) as u16),
+            _ => SyncReason::Other { ec, iss },
+        }
+    }
+}
+
+/// Reads `ESR_ELx`, for whichever EL the `el1`/`el2`/`el3` feature configures, or the current EL
+/// at runtime if none is configured.
+fn read_esr_elx() -> u64 {
+    let esr: u64;
+    #[cfg(feature = "el1")]
+    // SAFETY: Reading ESR_EL1 is always safe.
+    unsafe {
+        asm!("mrs {esr}, esr_el1", esr = out(reg) esr, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(feature = "el2")]
+    // SAFETY: Reading ESR_EL2 is always safe.
+    unsafe {
+        asm!("mrs {esr}, esr_el2", esr = out(reg) esr, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(feature = "el3")]
+    // SAFETY: Reading ESR_EL3 is always safe.
+    unsafe {
+        asm!("mrs {esr}, esr_el3", esr = out(reg) esr, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(not(any(feature = "el1", feature = "el2", feature = "el3")))]
+    {
+        esr = match current_el() {
+            1 => {
+                let value: u64;
+                // SAFETY: Reading ESR_EL1 is always safe.
+                unsafe {
+                    asm!("mrs {value}, esr_el1", value = out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }
+            2 => {
+                let value: u64;
+                // SAFETY: Reading ESR_EL2 is always safe.
+                unsafe {
+                    asm!("mrs {value}, esr_el2", value = out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }
+            3 => {
+                let value: u64;
+                // SAFETY: Reading ESR_EL3 is always safe.
+                unsafe {
+                    asm!("mrs {value}, esr_el3", value = out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }
+            _ => panic!("Unexpected EL"),
+        };
+    }
+    esr
+}
+
+/// Reads `FAR_ELx`, for whichever EL the `el1`/`el2`/`el3` feature configures, or the current EL
+/// at runtime if none is configured.
+fn read_far_elx() -> u64 {
+    let far: u64;
+    #[cfg(feature = "el1")]
+    // SAFETY: Reading FAR_EL1 is always safe.
+    unsafe {
+        asm!("mrs {far}, far_el1", far = out(reg) far, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(feature = "el2")]
+    // SAFETY: Reading FAR_EL2 is always safe.
+    unsafe {
+        asm!("mrs {far}, far_el2", far = out(reg) far, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(feature = "el3")]
+    // SAFETY: Reading FAR_EL3 is always safe.
+    unsafe {
+        asm!("mrs {far}, far_el3", far = out(reg) far, options(nomem, nostack, preserves_flags));
+    }
+    #[cfg(not(any(feature = "el1", feature = "el2", feature = "el3")))]
+    {
+        far = match current_el() {
+            1 => {
+                let value: u64;
+                // SAFETY: Reading FAR_EL1 is always safe.
+                unsafe {
+                    asm!("mrs {value}, far_el1", value = out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }
+            2 => {
+                let value: u64;
+                // SAFETY: Reading FAR_EL2 is always safe.
+                unsafe {
+                    asm!("mrs {value}, far_el2", value = out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }
+            3 => {
+                let value: u64;
+                // SAFETY: Reading FAR_EL3 is always safe.
+                unsafe {
+                    asm!("mrs {value}, far_el3", value = out(reg) value, options(nomem, nostack, preserves_flags));
+                }
+                value
+            }
+            _ => panic!("Unexpected EL"),
+        };
+    }
+    far
+}
+
+#[cfg(not(any(feature = "el1", feature = "el2", feature = "el3")))]
+fn current_el() -> u64 {
+    let current_el: u64;
+    // SAFETY: Reading CurrentEL is always safe.
+    unsafe {
+        asm!(
+            "mrs {current_el}, CurrentEL",
+            options(nomem, nostack, preserves_flags),
+            current_el = out(reg) current_el,
+        );
+    }
+    (current_el >> 2) & 0b11
+}
+
+// Every vector kind is dispatched to a symbol the application can override. Any slot the
+// application does not define falls back to `DefaultHandler`, which itself falls back to a
+// built-in handler that panics with the decoded syndrome and a register dump.
+global_asm!(
+    ".weak SyncException",
+    "SyncException:",
+    "mov x1, #0",
+    "b DefaultHandler",
+    ".weak Irq",
+    "Irq:",
+    "mov x1, #1",
+    "b DefaultHandler",
+    ".weak Fiq",
+    "Fiq:",
+    "mov x1, #2",
+    "b DefaultHandler",
+    ".weak SError",
+    "SError:",
+    "mov x1, #3",
+    "b DefaultHandler",
+    ".weak DefaultHandler",
+    "DefaultHandler:",
+    "b __aarch64_rt_default_handler",
+);
+
+unsafe extern "C" {
+    safe fn SyncException(frame: *mut ExceptionFrame, kind: u64);
+    safe fn Irq(frame: *mut ExceptionFrame, kind: u64);
+    safe fn Fiq(frame: *mut ExceptionFrame, kind: u64);
+    safe fn SError(frame: *mut ExceptionFrame, kind: u64);
+}
+
+/// Called from the vector table assembly once it has saved the interrupted context. Dispatches to
+/// whichever of `SyncException`, `Irq`, `Fiq` or `SError` the application registered with
+/// [`exception!`], or to the weak default if it didn't.
+///
+/// # Safety
+///
+/// `frame` must point to a valid, exclusively-owned [`ExceptionFrame`] on the exception stack,
+/// which the caller will restore and `eret` from once this returns.
+#[unsafe(export_name = "__aarch64_rt_handle_exception")]
+unsafe extern "C" fn handle_exception(frame: *mut ExceptionFrame, kind: u64) {
+    match kind {
+        0 => SyncException(frame, kind),
+        1 => Irq(frame, kind),
+        2 => Fiq(frame, kind),
+        3 => SError(frame, kind),
+        _ => unreachable!("the vector table only ever passes a kind in 0..=3"),
+    }
+}
+
+/// Crate-provided implementation of `DefaultHandler`, used when the application does not register
+/// one itself via `exception!(DefaultHandler, ...)`.
+///
+/// # Safety
+///
+/// Only ever reached from the vector table (via the weak `DefaultHandler` stub) with a valid
+/// frame pointer.
+#[unsafe(export_name = "__aarch64_rt_default_handler")]
+extern "C" fn default_handler(frame: *mut ExceptionFrame, kind: u64) -> ! {
+    // SAFETY: see above.
+    let frame = unsafe { &*frame };
+    let syndrome = Syndrome::decode(kind);
+    panic!("unhandled {syndrome:?} at elr={:#x}\n{frame:#x?}", frame.elr);
+}
+
+/// Decodes the [`Syndrome`] for the given exception `kind`.
+///
+/// Not part of the public API; used by the [`exception!`] macro expansion.
+#[doc(hidden)]
+pub fn __decode_syndrome(kind: u64) -> Syndrome {
+    Syndrome::decode(kind)
+}
+
+/// Registers a handler for one of the exception vectors: `SyncException`, `Irq`, `Fiq` or
+/// `SError`, or `DefaultHandler` to override the crate's built-in panic handler.
+///
+/// Any vector that is not registered falls back to `DefaultHandler`, which panics with the
+/// decoded [`Syndrome`] and a dump of the [`ExceptionFrame`].
+///
+/// Example:
+///
+/// ```rust
+/// use aarch64_rt::exception;
+///
+/// exception!(SyncException, fn handle_sync(frame, syndrome) {
+///     panic!("sync exception: {syndrome:?} at elr={:#x}", frame.elr);
+/// });
+/// ```
+#[macro_export]
+macro_rules! exception {
+    ($name:ident, fn $handler:ident($frame:ident, $syndrome:ident) $body:block) => {
+        #[unsafe(export_name = stringify!($name))]
+        extern "C" fn $handler(frame: *mut $crate::ExceptionFrame, kind: u64) {
+            // SAFETY: only ever called from the vector table with a valid frame pointer.
+            let $frame: &mut $crate::ExceptionFrame = unsafe { &mut *frame };
+            let $syndrome: $crate::Syndrome = $crate::__private::__decode_syndrome(kind);
+            $body
+        }
+    };
+}
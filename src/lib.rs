@@ -16,22 +16,29 @@
 compile_error!("Only one `el` feature may be enabled at once.");
 
 mod entry;
+#[cfg(feature = "exceptions")]
+mod exceptions;
 #[cfg(feature = "initial-pagetable")]
 mod pagetable;
+#[cfg(any(feature = "smp", feature = "psci"))]
+mod smp;
 
-#[cfg(feature = "initial-pagetable")]
+#[cfg(any(feature = "initial-pagetable", feature = "exceptions"))]
 #[doc(hidden)]
 pub mod __private {
+    #[cfg(feature = "exceptions")]
+    pub use crate::exceptions::__decode_syndrome;
+    #[cfg(feature = "initial-pagetable")]
     pub use crate::pagetable::{__enable_mmu_el1, __enable_mmu_el2, __enable_mmu_el3};
 }
 
-#[cfg(any(feature = "exceptions", feature = "psci"))]
-use core::arch::asm;
 #[cfg(feature = "exceptions")]
-use core::arch::global_asm;
+use core::arch::asm;
 #[cfg(not(feature = "initial-pagetable"))]
 use core::arch::naked_asm;
 pub use entry::secondary_entry;
+#[cfg(feature = "exceptions")]
+pub use exceptions::{ExceptionFrame, SyncReason, Syndrome};
 #[cfg(all(feature = "initial-pagetable", feature = "el1"))]
 pub use pagetable::DEFAULT_TCR_EL1 as DEFAULT_TCR;
 #[cfg(all(feature = "initial-pagetable", feature = "el2"))]
@@ -43,6 +50,10 @@ pub use pagetable::{
     DEFAULT_MAIR, DEFAULT_SCTLR, DEFAULT_TCR_EL1, DEFAULT_TCR_EL2, DEFAULT_TCR_EL3,
     InitialPagetable,
 };
+#[cfg(any(feature = "smp", feature = "psci"))]
+pub use smp::{CpuBringup, StackPool, affinity, start_core};
+#[cfg(feature = "psci")]
+pub use smp::{AffinityState, Psci, affinity_info, stop_core};
 
 #[cfg(not(feature = "initial-pagetable"))]
 #[unsafe(naked)]
@@ -52,9 +63,6 @@ extern "C" fn enable_mmu() {
     naked_asm!("ret")
 }
 
-#[cfg(feature = "exceptions")]
-global_asm!(include_str!("exceptions.S"));
-
 /// Sets the appropriate vbar to point to our `vector_table`, if the `exceptions` feature is
 /// enabled.
 extern "C" fn set_exception_vector() {
@@ -182,6 +190,49 @@ macro_rules! entry {
     };
 }
 
+/// Registers a hook to run in [`entry`][crate] immediately after the MMU is enabled, but before
+/// bss is zeroed and the stack is set up.
+///
+/// This is useful for board-specific early init, such as a DRAM controller or ECC scrub, that must
+/// run before `.bss` is touched. At the point this hook runs, only the MMU is guaranteed to be
+/// set up: there is no valid stack and bss has not been zeroed, so the hook must not push to the
+/// stack, rely on any `static`, or call any function that might (including allocating one on the
+/// compiler's behalf by spilling registers).
+///
+/// The hook is entered with `bl` and must return with `ret` to the address in x30. It may clobber
+/// x0-x18, but must leave x19-x28 unchanged: `entry` keeps its boot parameters there across the
+/// call.
+///
+/// If no hook is registered, a no-op default is used, so existing images are unaffected.
+///
+/// Example:
+///
+/// ```rust
+/// use aarch64_rt::pre_init;
+///
+/// pre_init!(scrub_ecc);
+///
+/// unsafe extern "C" fn scrub_ecc() {
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! pre_init {
+    ($name:path) => {
+        /// # Safety
+        ///
+        /// Called by `entry` with only the MMU set up: no stack and no zeroed bss. See
+        /// [`pre_init!`] for the full clobber contract.
+        #[unsafe(naked)]
+        #[unsafe(export_name = "pre_init")]
+        unsafe extern "C" fn __pre_init() {
+            // A plain tail branch: unlike a `bl`/`ret` pair, this never pushes a return address
+            // anywhere, so it upholds the no-stack contract regardless of optimization level.
+            core::arch::naked_asm!("b {name}", name = sym $name)
+        }
+    };
+}
+
 /// A stack for some CPU core.
 ///
 /// This is used by the [`entry!`] macro to reserve space for the boot stack.
@@ -210,93 +261,3 @@ impl StackPage {
     }
 }
 
-#[cfg(feature = "psci")]
-#[repr(C, align(16))] // align to the aarch64 stack requirements
-struct StartCoreStack<F> {
-    trampoline_ptr: unsafe extern "C" fn(*mut StartCoreStack<F>) -> !,
-    entry: Option<F>,
-}
-
-#[cfg(feature = "psci")]
-/// Issues a PSCI CPU_ON call to start the CPU core with the given MPIDR.
-///
-/// This starts the core with an assembly entry point which will enable the MMU, disable trapping of
-/// floating point instructions, initialise the stack pointer to the given value, and then jump to
-/// the given Rust entry point function, passing it the given argument value.
-///
-/// The closure passed as `rust_entry` **should never return**. Because the
-/// [never type has not been stabilized](https://github.com/rust-lang/rust/issues/35121)), this
-/// cannot be enforced by the type system yet.
-///
-/// # Safety
-///
-/// `stack` must point to a region of memory which is reserved for this core's stack. It must remain
-/// valid as long as the core is running, and there must not be any other access to it during that
-/// time. It must be mapped both for the current core to write to it (to pass initial parameters)
-/// and in the initial page table which the core being started will used, with the same memory
-/// attributes for both.
-pub unsafe fn start_core<C: smccc::Call, F, const N: usize>(
-    mpidr: u64,
-    stack: *mut Stack<N>,
-    rust_entry: F,
-) -> Result<(), smccc::psci::Error>
-where
-    // TODO: change to FnOnce() -> ! when the never type is stabilized:
-    // https://github.com/rust-lang/rust/issues/35121
-    F: FnOnce() + Send + 'static,
-{
-    const {
-        assert!(
-            core::mem::size_of::<StartCoreStack<F>>() <= core::mem::size_of::<Stack<N>>(),
-            "the `rust_entry` closure is too big to fit in the core stack"
-        );
-    }
-
-    assert!(stack.is_aligned());
-    let stack_end = stack.wrapping_add(1) as *mut StartCoreStack<F>;
-
-    // Write trampoline and the entry closure to the stack, so the assembly entry point can jump to it.
-    // SAFETY: Our caller promised that the stack is valid and nothing else will access it.
-    unsafe {
-        *stack_end.wrapping_sub(1) = StartCoreStack {
-            trampoline_ptr: trampoline::<F>,
-            entry: Some(rust_entry),
-        };
-    };
-
-    // Wait for the stores above to complete before starting the secondary CPU core.
-    dsb_st();
-
-    smccc::psci::cpu_on::<C>(
-        mpidr,
-        secondary_entry as usize as _,
-        stack_end.wrapping_sub(1) as usize as _,
-    )
-}
-
-#[cfg(feature = "psci")]
-unsafe extern "C" fn trampoline<F>(start_args_ptr: *mut StartCoreStack<F>) -> !
-where
-    // TODO: change to FnOnce() -> ! when the never type is stabilized:
-    // https://github.com/rust-lang/rust/issues/35121
-    F: FnOnce() + Send + 'static,
-{
-    // SAFETY: `start_args_ptr` was created from a valid `F` in `start_core` and the memory is valid
-    // for the lifetime of the core.
-    let start_args = unsafe { &mut *start_args_ptr };
-    let entry = core::mem::take(&mut start_args.entry)
-        .expect("entry object should only ever be taken once");
-
-    entry();
-
-    panic!("rust_entry function passed to start_core should never return");
-}
-
-/// Data synchronisation barrier that waits for stores to complete, for the full system.
-#[cfg(feature = "psci")]
-fn dsb_st() {
-    // SAFETY: A synchronisation barrier is always safe.
-    unsafe {
-        asm!("dsb st", options(nostack));
-    }
-}
@@ -0,0 +1,342 @@
+// Copyright 2025 The aarch64-rt Authors.
+// This project is dual-licensed under Apache 2.0 and MIT terms.
+// See LICENSE-APACHE and LICENSE-MIT for details.
+
+//! Secondary-core (SMP) lifecycle.
+//!
+//! [`start_core`] is generic over how a core is actually released, via the [`CpuBringup`] trait;
+//! [`Psci`] is the default backend, using the PSCI `CPU_ON` call. [`stop_core`] and
+//! [`affinity_info`] are always PSCI calls, since `CPU_OFF` and `AFFINITY_INFO` have no equivalent
+//! in other boot protocols such as spin-tables.
+
+use crate::{Stack, secondary_entry};
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The affinity fields of `MPIDR_EL1` (Aff3:Aff2:Aff1:Aff0), excluding the `U` and `MT` bits and
+/// the reserved bits above Aff3.
+const MPIDR_AFFINITY_MASK: u64 = (0xff << 32) | 0xff_ffff;
+
+/// Reads the current core's affinity value from `MPIDR_EL1`.
+///
+/// This is the canonical value to pass as the `mpidr` argument of [`start_core`] and
+/// [`affinity_info`] for this core, with the `U`, `MT` and reserved bits masked out.
+pub fn affinity() -> u64 {
+    let mpidr: u64;
+    // SAFETY: Reading MPIDR_EL1 is always safe.
+    unsafe {
+        asm!(
+            "mrs {mpidr}, mpidr_el1",
+            mpidr = out(reg) mpidr,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    mpidr & MPIDR_AFFINITY_MASK
+}
+
+/// The power state of a core, as reported by PSCI `AFFINITY_INFO`.
+#[cfg(feature = "psci")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AffinityState {
+    /// The core is online.
+    On,
+    /// The core is offline.
+    Off,
+    /// The core is in the process of coming online.
+    OnPending,
+}
+
+/// Issues a PSCI `AFFINITY_INFO` call to find out whether the core with the given affinity value
+/// has come online.
+///
+/// `mpidr` should be a canonical affinity value, as returned by [`affinity`].
+#[cfg(feature = "psci")]
+pub fn affinity_info<C: smccc::Call>(mpidr: u64) -> Result<AffinityState, smccc::psci::Error> {
+    match smccc::psci::affinity_info::<C>(mpidr, 0)? {
+        0 => Ok(AffinityState::On),
+        1 => Ok(AffinityState::Off),
+        2 => Ok(AffinityState::OnPending),
+        state => panic!("Unexpected PSCI AFFINITY_INFO state {state}"),
+    }
+}
+
+/// Issues a PSCI `CPU_OFF` call to park the current core.
+///
+/// This should be called from a secondary core's Rust entry point to cleanly shut itself down
+/// once it has no more work to do. It does not return on success; it only returns if the call
+/// itself failed.
+///
+/// # Safety
+///
+/// The caller must ensure that nothing else still expects this core to be running, and that
+/// this core's stack is not released back to a [`StackPool`] until the core has actually stopped,
+/// e.g. once [`affinity_info`] reports it as [`AffinityState::Off`].
+#[cfg(feature = "psci")]
+pub unsafe fn stop_core<C: smccc::Call>() -> smccc::psci::Error {
+    smccc::psci::cpu_off::<C>()
+}
+
+/// A mechanism for bringing a secondary CPU core online.
+///
+/// The boot protocol for secondary cores is platform-specific: most commonly it is PSCI (see
+/// [`Psci`]), but some platforms instead use a spin-table or a firmware mailbox that is released
+/// by writing an entry point and stack pointer to a per-core slot and signalling with `sev`.
+/// [`start_core`] is generic over this trait so it works the same way regardless of which
+/// mechanism the platform uses.
+pub trait CpuBringup {
+    /// The error type returned if releasing the core fails.
+    type Error;
+
+    /// Releases the secondary core identified by `mpidr`, directing it to begin executing at
+    /// `entry_point` with `context` passed as its initial argument in `x0`.
+    ///
+    /// # Safety
+    ///
+    /// `entry_point` must be the address of a valid entry point for the core being released,
+    /// expecting `context` as described above (for [`Psci`], this means `entry_point` must be
+    /// [`secondary_entry`][crate::secondary_entry] and `context` the stack pointer it expects, as
+    /// set up by [`start_core`]).
+    unsafe fn release(
+        &self,
+        mpidr: u64,
+        entry_point: usize,
+        context: usize,
+    ) -> Result<(), Self::Error>;
+}
+
+/// A [`CpuBringup`] implementor using the PSCI `CPU_ON` call.
+///
+/// This is the default, and matches the behaviour `start_core` had before it was generalised over
+/// [`CpuBringup`].
+#[cfg(feature = "psci")]
+pub struct Psci<C: smccc::Call> {
+    _call: core::marker::PhantomData<C>,
+}
+
+#[cfg(feature = "psci")]
+impl<C: smccc::Call> Psci<C> {
+    /// Creates a new `Psci` bringup backend, using `C` to make the underlying SMCCC call.
+    pub const fn new() -> Self {
+        Self {
+            _call: core::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "psci")]
+impl<C: smccc::Call> Default for Psci<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "psci")]
+impl<C: smccc::Call> CpuBringup for Psci<C> {
+    type Error = smccc::psci::Error;
+
+    unsafe fn release(
+        &self,
+        mpidr: u64,
+        entry_point: usize,
+        context: usize,
+    ) -> Result<(), Self::Error> {
+        smccc::psci::cpu_on::<C>(mpidr, entry_point as u64, context as u64)
+    }
+}
+
+#[repr(C, align(16))] // align to the aarch64 stack requirements
+struct StartCoreStack<F> {
+    trampoline_ptr: unsafe extern "C" fn(*mut StartCoreStack<F>) -> !,
+    entry: Option<F>,
+}
+
+/// Releases the CPU core with the given MPIDR using the given [`CpuBringup`] backend.
+///
+/// This starts the core with an assembly entry point which will enable the MMU, disable trapping of
+/// floating point instructions, initialise the stack pointer to the given value, and then jump to
+/// the given `rust_entry` closure.
+///
+/// The closure passed as `rust_entry` **should never return**. Because the
+/// [never type has not been stabilized](https://github.com/rust-lang/rust/issues/35121)), this
+/// cannot be enforced by the type system yet.
+///
+/// # Safety
+///
+/// `stack` must point to a region of memory which is reserved for this core's stack. It must remain
+/// valid as long as the core is running, and there must not be any other access to it during that
+/// time. It must be mapped both for the current core to write to it (to pass initial parameters)
+/// and in the initial page table which the core being started will used, with the same memory
+/// attributes for both.
+pub unsafe fn start_core<B: CpuBringup, F, const N: usize>(
+    bringup: &B,
+    mpidr: u64,
+    stack: *mut Stack<N>,
+    rust_entry: F,
+) -> Result<(), B::Error>
+where
+    // TODO: change to FnOnce() -> ! when the never type is stabilized:
+    // https://github.com/rust-lang/rust/issues/35121
+    F: FnOnce() + Send + 'static,
+{
+    const {
+        assert!(
+            core::mem::size_of::<StartCoreStack<F>>() <= core::mem::size_of::<Stack<N>>(),
+            "the `rust_entry` closure is too big to fit in the core stack"
+        );
+    }
+
+    assert!(stack.is_aligned());
+    let stack_end = stack.wrapping_add(1) as *mut StartCoreStack<F>;
+
+    // Write trampoline and the entry closure to the stack, so the assembly entry point can jump to it.
+    // SAFETY: Our caller promised that the stack is valid and nothing else will access it.
+    unsafe {
+        *stack_end.wrapping_sub(1) = StartCoreStack {
+            trampoline_ptr: trampoline::<F>,
+            entry: Some(rust_entry),
+        };
+    };
+
+    // Wait for the stores above to complete before starting the secondary CPU core.
+    dsb_st();
+
+    // SAFETY: `secondary_entry` is a valid entry point expecting the stack pointer we just wrote
+    // `stack_end.wrapping_sub(1)` to as its `context`, per the `CpuBringup::release` contract.
+    unsafe {
+        bringup.release(
+            mpidr,
+            secondary_entry as usize,
+            stack_end.wrapping_sub(1) as usize,
+        )
+    }
+}
+
+unsafe extern "C" fn trampoline<F>(start_args_ptr: *mut StartCoreStack<F>) -> !
+where
+    // TODO: change to FnOnce() -> ! when the never type is stabilized:
+    // https://github.com/rust-lang/rust/issues/35121
+    F: FnOnce() + Send + 'static,
+{
+    // SAFETY: `start_args_ptr` was created from a valid `F` in `start_core` and the memory is valid
+    // for the lifetime of the core.
+    let start_args = unsafe { &mut *start_args_ptr };
+    let entry = core::mem::take(&mut start_args.entry)
+        .expect("entry object should only ever be taken once");
+
+    entry();
+
+    panic!("rust_entry function passed to start_core should never return");
+}
+
+/// Data synchronisation barrier that waits for stores to complete, for the full system.
+fn dsb_st() {
+    // SAFETY: A synchronisation barrier is always safe.
+    unsafe {
+        asm!("dsb st", options(nostack));
+    }
+}
+
+/// A pool of statically-allocated, correctly-aligned stacks for secondary cores, for use with
+/// [`start_core`].
+///
+/// This replaces manually carving up a single large stack allocation: each call to [`claim`][Self::claim]
+/// hands out one of the `N` stacks, each `PAGES` pages in size, and tracks which ones are already
+/// in use so the same stack is never handed out twice.
+pub struct StackPool<const N: usize, const PAGES: usize> {
+    stacks: [UnsafeCell<Stack<PAGES>>; N],
+    claimed: [AtomicBool; N],
+}
+
+// SAFETY: a slot's `UnsafeCell` is only ever dereferenced by whoever holds it "checked out" via
+// `claimed`, which `claim`/`release` maintain atomically, so at most one caller at a time can
+// access a given slot's contents. This is what makes it sound to use a `StackPool` from a `static`.
+unsafe impl<const N: usize, const PAGES: usize> Sync for StackPool<N, PAGES> {}
+
+impl<const N: usize, const PAGES: usize> StackPool<N, PAGES> {
+    /// Creates a new pool of `N` stacks, each `PAGES` pages in size, all initially free.
+    pub const fn new() -> Self {
+        Self {
+            stacks: [const { UnsafeCell::new(Stack::new()) }; N],
+            claimed: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// Claims a free stack from the pool.
+    ///
+    /// Returns `None` if every stack in the pool is already in use. The returned pointer is
+    /// correctly aligned and does not overlap any other stack, claimed or not, so it may be
+    /// passed directly to [`start_core`].
+    pub fn claim(&self) -> Option<*mut Stack<PAGES>> {
+        for (i, claimed) in self.claimed.iter().enumerate() {
+            if claimed
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(self.stacks[i].get());
+            }
+        }
+        None
+    }
+
+    /// Returns a stack previously obtained from [`claim`][Self::claim] to the pool, so it may be
+    /// handed out again.
+    ///
+    /// # Safety
+    ///
+    /// `stack` must have been obtained from [`claim`][Self::claim] on this same pool. The core
+    /// using it must have stopped (e.g. via [`stop_core`]), and nothing else may access the
+    /// stack's memory until it is claimed again.
+    pub unsafe fn release(&self, stack: *mut Stack<PAGES>) {
+        let base = self.stacks[0].get();
+        // SAFETY: our caller guarantees `stack` was obtained from `claim` on this pool, so it is
+        // one of the `N` elements of `self.stacks`.
+        let index = unsafe { stack.offset_from(base) } as usize;
+        self.claimed[index].store(false, Ordering::Release);
+    }
+}
+
+impl<const N: usize, const PAGES: usize> Default for StackPool<N, PAGES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claim_exhausts_after_n_calls() {
+        let pool = StackPool::<3, 1>::new();
+        assert!(pool.claim().is_some());
+        assert!(pool.claim().is_some());
+        assert!(pool.claim().is_some());
+        assert!(pool.claim().is_none());
+    }
+
+    #[test]
+    fn claimed_slots_do_not_alias() {
+        let pool = StackPool::<4, 1>::new();
+        let claimed = [0; 4].map(|_| pool.claim().expect("pool should have a free slot"));
+        for (i, a) in claimed.iter().enumerate() {
+            for b in &claimed[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn release_lets_a_slot_be_claimed_again() {
+        let pool = StackPool::<1, 1>::new();
+        let stack = pool.claim().expect("pool should start with a free slot");
+        assert!(pool.claim().is_none());
+
+        // SAFETY: `stack` was just claimed above and nothing else accesses it.
+        unsafe {
+            pool.release(stack);
+        }
+
+        assert_eq!(pool.claim(), Some(stack));
+    }
+}